@@ -1,6 +1,11 @@
 use clap::Parser;
 
 /// hq is like jq but for HTML data.
+///
+/// `-f`, `-s`, and `-a` each build passes in the order they're repeated, but
+/// the three flags don't share an ordering with each other: all `-f` passes
+/// run, then all `-s` passes, then all `-a` passes, regardless of how they
+/// were interleaved on the command line. See `build_pipeline` in `main.rs`.
 #[derive(Parser, Debug)]
 pub struct Cli {
     /// A css expression
@@ -9,4 +14,51 @@ pub struct Cli {
     /// An action
     #[clap(short = 'a', long = "action", multiple_occurrences = true)]
     actions: Vec<String>,
+    /// A sanitizer configuration, e.g. `element:p,element:a,attribute:a.href,scheme:http,defang-images`
+    #[clap(short = 's', long = "sanitize", multiple_occurrences = true)]
+    sanitizers: Vec<String>,
+    /// Input files to process; each is run through the pipeline
+    /// independently. Omit to read a single document from stdin.
+    #[clap(value_name = "FILE")]
+    files: Vec<String>,
+    /// Treat stdin as a stream of NUL-separated documents instead of a
+    /// single document, running the pipeline independently over each.
+    #[clap(long = "null-data")]
+    null_data: bool,
+    /// Output format: `html` (default), `json`, `ndjson`, or `csv`. Only
+    /// `html` emits the rewritten document; the others emit the records
+    /// collected by `extract` clauses instead.
+    #[clap(short = 'o', long = "format", default_value = "html")]
+    format: String,
+}
+
+impl Cli {
+    /// The raw `-f` expressions, each a bare CSS selector to filter out.
+    pub fn filters(&self) -> &[String] {
+        &self.filters
+    }
+
+    /// The raw `-a` expressions, each parsed by [`crate::query::parse_action`].
+    pub fn actions(&self) -> &[String] {
+        &self.actions
+    }
+
+    /// The raw `-s` expressions, each parsed by [`crate::query::parse_sanitize`].
+    pub fn sanitizers(&self) -> &[String] {
+        &self.sanitizers
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn null_data(&self) -> bool {
+        self.null_data
+    }
+
+    /// The raw `-o/--format` value, parsed by [`crate::exec::OutputMode`]'s
+    /// `FromStr` impl.
+    pub fn format(&self) -> &str {
+        &self.format
+    }
 }
@@ -0,0 +1,41 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+/// The error type returned by the pipeline's build and execution steps.
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Error {
+        Error {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Wraps a MiniJinja compile/render error with the selector and template
+    /// that triggered it, so a failure can be traced back to the pipeline.
+    pub fn template(selector: &str, template: &str, source: minijinja::Error) -> Error {
+        Error {
+            message: format!(
+                "template error for selector `{selector}` (template `{template}`): {source}"
+            ),
+            source: Some(Box::new(source)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn StdError + 'static))
+    }
+}
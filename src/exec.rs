@@ -1,17 +1,50 @@
 use lol_html::HtmlRewriter;
 use minijinja::Environment;
 use serde::Serialize;
+use serde_json::Value;
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
+use std::str::FromStr;
 
+use crate::error::Error;
 use crate::pipeline::{PassState, PipelineSink};
 
 thread_local! {
     static STATE: RefCell<Option<ExecState>> = RefCell::new(None);
 }
 
+/// What shape `Exec::exec` should produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// The rewritten HTML document (the default).
+    #[default]
+    Html,
+    /// All extracted records as a single JSON array.
+    Json,
+    /// All extracted records as newline-delimited JSON.
+    Ndjson,
+    /// All extracted records as CSV, columns taken from the union of record keys.
+    Csv,
+}
+
+impl FromStr for OutputMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "html" => Ok(OutputMode::Html),
+            "json" => Ok(OutputMode::Json),
+            "ndjson" => Ok(OutputMode::Ndjson),
+            "csv" => Ok(OutputMode::Csv),
+            other => Err(Error::new(format!("unknown output format `{other}`"))),
+        }
+    }
+}
+
 pub struct ExecState {
     output: Vec<u8>,
+    records: Vec<Value>,
 }
 
 impl ExecState {
@@ -23,28 +56,162 @@ impl ExecState {
         self.output.extend_from_slice(chunk);
     }
 
-    pub fn render_template<S: Serialize>(&mut self, source: &str, ctx: S) -> String {
-        // TODO: error handling and caching
-        let mut env = Environment::new();
-        env.add_template("tmpl.html", source).unwrap();
-        let tmpl = env.get_template("tmpl.html").unwrap();
-        tmpl.render(ctx).unwrap()
+    pub fn push_record(&mut self, record: Value) {
+        self.records.push(record);
+    }
+
+    /// Renders an already-registered template by name (the pipeline registers
+    /// every `set_inner_content` template under its own source as the name,
+    /// see `Pipeline::build`), passed the shared environment for lookup.
+    pub fn render_template<S: Serialize>(
+        &mut self,
+        environment: &Environment,
+        template: &str,
+        ctx: S,
+    ) -> Result<String, minijinja::Error> {
+        environment.get_template(template)?.render(ctx)
     }
 }
 
-#[derive(Debug)]
 pub struct Exec<'a> {
     pub(crate) rewriter: HtmlRewriter<'a, PipelineSink<'a>>,
     pub(crate) root_state: Rc<RefCell<PassState>>,
+    pub(crate) output_mode: OutputMode,
+    pub(crate) environment: Rc<Environment<'a>>,
+}
+
+impl<'a> fmt::Debug for Exec<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exec")
+            .field("output_mode", &self.output_mode)
+            .finish()
+    }
 }
 
 impl<'a> Exec<'a> {
-    pub fn exec(&mut self, input: &[u8]) -> String {
+    pub fn exec(&mut self, input: &[u8]) -> Result<String, Error> {
         STATE.with(|state| {
-            *state.borrow_mut() = Some(ExecState { output: Vec::new() });
+            *state.borrow_mut() = Some(ExecState {
+                output: Vec::new(),
+                records: Vec::new(),
+            });
         });
-        self.rewriter.write(input).unwrap();
+        self.rewriter
+            .write(input)
+            .map_err(|e| Error::new(e.to_string()))?;
         let state = STATE.with(|state| state.borrow_mut().take()).unwrap();
-        String::from_utf8(state.output).unwrap()
+        Ok(match self.output_mode {
+            OutputMode::Html => {
+                String::from_utf8(state.output).map_err(|e| Error::new(e.to_string()))?
+            }
+            OutputMode::Json => {
+                serde_json::to_string(&state.records).map_err(|e| Error::new(e.to_string()))?
+            }
+            OutputMode::Ndjson => state
+                .records
+                .iter()
+                .map(|record| {
+                    serde_json::to_string(record).map_err(|e| Error::new(e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join("\n"),
+            OutputMode::Csv => records_to_csv(&state.records),
+        })
+    }
+}
+
+fn records_to_csv(records: &[Value]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    for record in records {
+        if let Value::Object(map) = record {
+            for key in map.keys() {
+                if !columns.contains(&key.as_str()) {
+                    columns.push(key);
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    push_csv_row(&mut out, columns.iter().copied());
+    for record in records {
+        let Value::Object(map) = record else { continue };
+        push_csv_row(
+            &mut out,
+            columns.iter().map(|col| match map.get(*col) {
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            }),
+        );
+    }
+    out
+}
+
+fn push_csv_row<S: AsRef<str>>(out: &mut String, fields: impl Iterator<Item = S>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_csv_field(out, field.as_ref());
+    }
+    out.push('\n');
+}
+
+fn push_csv_field(out: &mut String, field: &str) {
+    if field.contains([',', '"', '\n']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn output_mode_from_str_parses_known_formats() {
+        assert_eq!(OutputMode::from_str("html").unwrap(), OutputMode::Html);
+        assert_eq!(OutputMode::from_str("json").unwrap(), OutputMode::Json);
+        assert_eq!(OutputMode::from_str("ndjson").unwrap(), OutputMode::Ndjson);
+        assert_eq!(OutputMode::from_str("csv").unwrap(), OutputMode::Csv);
+    }
+
+    #[test]
+    fn output_mode_from_str_rejects_unknown_format() {
+        assert!(OutputMode::from_str("yaml").is_err());
+    }
+
+    #[test]
+    fn records_to_csv_unions_columns_across_records() {
+        let records = vec![
+            json!({"title": "a", "url": "/a"}),
+            json!({"title": "b"}),
+        ];
+        let csv = records_to_csv(&records);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("title,url"));
+        assert_eq!(lines.next(), Some("a,/a"));
+        assert_eq!(lines.next(), Some("b,"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn push_csv_field_quotes_fields_with_special_characters() {
+        let mut out = String::new();
+        push_csv_field(&mut out, "has, comma");
+        assert_eq!(out, "\"has, comma\"");
+
+        let mut out = String::new();
+        push_csv_field(&mut out, "has \"quote\"");
+        assert_eq!(out, "\"has \"\"quote\"\"\"");
+
+        let mut out = String::new();
+        push_csv_field(&mut out, "plain");
+        assert_eq!(out, "plain");
     }
 }
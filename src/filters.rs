@@ -0,0 +1,94 @@
+//! HTML-aware MiniJinja filters available to every `set_inner_content` template.
+
+use minijinja::Environment;
+
+pub fn register(env: &mut Environment) {
+    env.add_filter("text", strip_tags);
+    env.add_filter("strip_tags", strip_tags);
+    env.add_filter("attr", attr);
+}
+
+/// Strips tags from an HTML fragment, leaving the visible text behind.
+fn strip_tags(value: String) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut in_tag = false;
+    for ch in value.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(ch),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Pulls a single attribute's value out of an HTML fragment, e.g.
+/// `{{ link|attr("href") }}`.
+///
+/// A bare `value.find("{name}=")` would also match inside an unrelated
+/// attribute that merely contains `name` as a substring (e.g. `data-href`
+/// when looking for `href`), so every candidate match is checked for an
+/// actual attribute boundary — preceded by whitespace, or the start of the
+/// fragment — before it's accepted.
+fn attr(value: String, name: String) -> String {
+    let needle = format!("{name}=");
+    let mut search_from = 0;
+    while let Some(found) = value[search_from..].find(&needle) {
+        let start = search_from + found;
+        let at_boundary = match value[..start].chars().next_back() {
+            None => true,
+            Some(c) => c.is_whitespace(),
+        };
+        if at_boundary {
+            let rest = &value[start + needle.len()..];
+            return match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    rest[1..].split(quote).next().unwrap_or_default().into()
+                }
+                _ => rest
+                    .split(|c: char| c.is_whitespace() || c == '>')
+                    .next()
+                    .unwrap_or_default()
+                    .into(),
+            };
+        }
+        search_from = start + 1;
+    }
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_tags_keeps_only_visible_text() {
+        assert_eq!(strip_tags("<a href=\"x\">click <b>here</b></a>".into()), "click here");
+    }
+
+    #[test]
+    fn attr_extracts_quoted_and_unquoted_values() {
+        assert_eq!(attr("<a href=\"/x\">".into(), "href".into()), "/x");
+        assert_eq!(attr("<a href='/x'>".into(), "href".into()), "/x");
+        assert_eq!(attr("<a href=/x>".into(), "href".into()), "/x");
+    }
+
+    #[test]
+    fn attr_ignores_unrelated_attribute_with_name_as_substring() {
+        assert_eq!(attr("<a data-href=\"evil\">x</a>".into(), "href".into()), "");
+    }
+
+    #[test]
+    fn attr_finds_the_real_attribute_after_a_lookalike() {
+        assert_eq!(
+            attr("<a data-href=\"evil\" href=\"/real\">x</a>".into(), "href".into()),
+            "/real"
+        );
+    }
+
+    #[test]
+    fn attr_returns_empty_when_attribute_absent() {
+        assert_eq!(attr("<a class=\"x\">".into(), "href".into()), "");
+    }
+}
@@ -1,18 +1,116 @@
+mod cli;
+mod error;
 mod exec;
+mod filters;
 mod pipeline;
+mod query;
 
-fn main() {
-    let mut pipeline = pipeline::Pipeline::new();
-    pipeline.add_pass(|pass| {
-        pass.filter("ul.menu");
-    });
-    pipeline.add_pass(|pass| {
-        pass.on("a[href]", |sel| {
-            sel.rewrite_attribute("href", "^http:", "https:");
-            sel.set_inner_content("{{ attributes|tojson }}");
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use clap::Parser;
+
+use cli::Cli;
+use error::Error;
+use exec::OutputMode;
+use pipeline::Pipeline;
+use query::Clause;
+
+/// Builds the pipeline from the CLI's flags.
+///
+/// Passes run in the order they're added here: every `-f`, then every `-s`,
+/// then every `-a`, each group preserving the order it was repeated on the
+/// command line. `clap`'s derive API collects `-f`/`-s`/`-a` into separate
+/// `Vec<String>` fields with no shared index, so the relative order between
+/// *different* flags is not tracked — `-a foo -f bar -a baz` runs exactly
+/// like `-a foo -a baz -f bar`. If that ever needs to match literal CLI
+/// order, build the `clap::Command` by hand and read `ArgMatches::indices_of`
+/// for each flag instead of relying on the derived `Vec<String>`s.
+fn build_pipeline(cli: &Cli) -> Result<Pipeline, Error> {
+    let mut pipeline = Pipeline::new();
+    pipeline.set_output_mode(OutputMode::from_str(cli.format())?);
+
+    for filter in cli.filters() {
+        pipeline.add_pass(|pass| pass.filter(filter));
+    }
+
+    for raw_sanitize in cli.sanitizers() {
+        let config = query::parse_sanitize(raw_sanitize)?;
+        pipeline.add_pass(|pass| pass.sanitize(config));
+    }
+
+    for raw_action in cli.actions() {
+        let action = query::parse_action(raw_action)?;
+        pipeline.add_pass(|pass| {
+            pass.on(&action.selector, move |sel| {
+                for clause in action.clauses {
+                    match clause {
+                        Clause::Filter => sel.filter(),
+                        Clause::RewriteAttr {
+                            attr,
+                            regex,
+                            replacement,
+                        } => sel.rewrite_attribute(&attr, &regex, &replacement),
+                        Clause::SetInner { template } => sel.set_inner_content(&template),
+                        Clause::Extract { fields } => sel.extract(
+                            fields.iter().map(|(k, f)| (k.as_str(), f.clone())).collect(),
+                        ),
+                    }
+                }
+            });
         });
-    });
+    }
+
+    Ok(pipeline)
+}
 
-    let mut exec = pipeline.build();
-    print!("{}", exec.exec(include_bytes!("../menu.html")));
+/// Reads every input document the CLI was asked to process: one per `FILE`
+/// argument, or one (or more, with `--null-data`) document from stdin.
+fn collect_inputs(cli: &Cli) -> Result<Vec<Vec<u8>>, Error> {
+    if !cli.files().is_empty() {
+        return cli
+            .files()
+            .iter()
+            .map(|path| std::fs::read(path).map_err(|e| Error::new(format!("{path}: {e}"))))
+            .collect();
+    }
+
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .map_err(|e| Error::new(e.to_string()))?;
+
+    Ok(if cli.null_data() {
+        input
+            .split(|&b| b == 0)
+            .filter(|doc| !doc.is_empty())
+            .map(|doc| doc.to_vec())
+            .collect()
+    } else {
+        vec![input]
+    })
+}
+
+fn run() -> Result<(), Error> {
+    let cli = Cli::parse();
+    let pipeline = build_pipeline(&cli)?;
+    let inputs = collect_inputs(&cli)?;
+
+    let mut stdout = io::stdout();
+    for output in pipeline.exec_many(&inputs)? {
+        stdout
+            .write_all(output.as_bytes())
+            .map_err(|e| Error::new(e.to_string()))?;
+        stdout
+            .write_all(b"\n")
+            .map_err(|e| Error::new(e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
 }
@@ -1,13 +1,16 @@
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::rc::Rc;
 
 use lol_html::html_content::{ContentType, Element};
-use lol_html::{element, HtmlRewriter, OutputSink, Settings};
-use minijinja::context;
+use lol_html::{element, text, HtmlRewriter, OutputSink, Settings};
+use minijinja::{context, Environment};
 use regex::Regex;
+use serde_json::{Map, Value};
 
-use crate::exec::{Exec, ExecState};
+use crate::error::Error;
+use crate::exec::{Exec, ExecState, OutputMode};
+use crate::filters;
 
 pub struct PipelineSink<'h> {
     pass_state: Rc<RefCell<PassState>>,
@@ -36,6 +39,7 @@ impl<'h> OutputSink for PipelineSink<'h> {
 #[derive(Debug, Default)]
 pub struct Pipeline {
     passes: Vec<Pass>,
+    output_mode: OutputMode,
 }
 
 #[derive(Debug)]
@@ -61,6 +65,132 @@ pub enum Action {
     SetInnerContent {
         template: String,
     },
+    Extract {
+        fields: Vec<(String, Field)>,
+    },
+    Sanitize {
+        config: Rc<SanitizeConfig>,
+    },
+}
+
+/// Allowlist configuration for [`Pass::sanitize`].
+///
+/// Any element not in `allowed_elements` is unwrapped (its content is kept,
+/// the tag itself is dropped); elements in `blacklisted_elements` (e.g.
+/// `script`, `style`) are dropped along with their content. Attributes not
+/// present in `allowed_attributes` for a given element are stripped, and
+/// URL-bearing attributes (`href`, `src`, `srcset`) whose scheme isn't in
+/// `allowed_schemes` are stripped too, to defang things like `javascript:`.
+#[derive(Debug, Clone, Default)]
+pub struct SanitizeConfig {
+    allowed_elements: BTreeSet<String>,
+    blacklisted_elements: BTreeSet<String>,
+    allowed_attributes: BTreeMap<String, BTreeSet<String>>,
+    allowed_schemes: BTreeSet<String>,
+    defang_images: bool,
+}
+
+impl SanitizeConfig {
+    pub fn new() -> SanitizeConfig {
+        SanitizeConfig::default()
+    }
+
+    pub fn allow_element(&mut self, name: &str) -> &mut Self {
+        self.allowed_elements.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn blacklist_element(&mut self, name: &str) -> &mut Self {
+        self.blacklisted_elements.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn allow_attribute(&mut self, element: &str, attr: &str) -> &mut Self {
+        self.allowed_attributes
+            .entry(element.to_ascii_lowercase())
+            .or_default()
+            .insert(attr.to_ascii_lowercase());
+        self
+    }
+
+    pub fn allow_scheme(&mut self, scheme: &str) -> &mut Self {
+        self.allowed_schemes.insert(scheme.to_ascii_lowercase());
+        self
+    }
+
+    pub fn defang_images(&mut self, yes: bool) -> &mut Self {
+        self.defang_images = yes;
+        self
+    }
+
+    /// A URL with no `:` in it is always allowed; otherwise the text before
+    /// the first `:` must be a well-formed, explicitly allowlisted scheme, so
+    /// a relative URL that contains a colon (e.g. `/a:b`) is denied as well.
+    /// Anything that merely *looks*
+    /// malformed (e.g. because of obfuscating whitespace) is denied rather
+    /// than let through, since that's the shape a bypass attempt takes.
+    fn scheme_allowed(&self, url: &str) -> bool {
+        // Browsers strip ASCII tab/newline/CR anywhere in a URL, and leading
+        // C0-control-or-space, before parsing its scheme — mirror that so
+        // `"java\tscript:"` or `" javascript:"` can't hide the real scheme
+        // from the check below.
+        let cleaned: String = url.chars().filter(|c| !matches!(c, '\t' | '\n' | '\r')).collect();
+        let cleaned = cleaned.trim_start_matches(|c: char| c.is_ascii_control() || c == ' ');
+
+        match cleaned.find(':') {
+            None => true,
+            Some(end) => {
+                let scheme = &cleaned[..end];
+                let looks_like_scheme = scheme
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+                looks_like_scheme && self.allowed_schemes.contains(&scheme.to_ascii_lowercase())
+            }
+        }
+    }
+
+    /// `srcset` is a comma-separated list of candidate URLs (each optionally
+    /// followed by a width/density descriptor), not one opaque URL — check
+    /// every candidate individually rather than the raw attribute value.
+    fn srcset_allowed(&self, value: &str) -> bool {
+        value.split(',').all(|candidate| {
+            candidate
+                .trim()
+                .split_whitespace()
+                .next()
+                .map_or(true, |url| self.scheme_allowed(url))
+        })
+    }
+}
+
+/// Where a single extracted field's value comes from.
+#[derive(Debug, Clone)]
+pub enum Field {
+    /// The value of an attribute on the matched element.
+    Attr(String),
+    /// The concatenated text content of the matched element.
+    Text,
+}
+
+impl Field {
+    fn wants_text(&self) -> bool {
+        matches!(self, Field::Text)
+    }
+}
+
+/// An in-progress record for a single open `Extract` match.
+///
+/// Kept on a stack so nested matches of the same selector (e.g. a
+/// `div.item` inside another `div.item`) each accumulate their own text.
+#[derive(Debug, Default)]
+struct ExtractFrame {
+    fields: Vec<(String, Field)>,
+    record: Map<String, Value>,
+    text_buf: String,
 }
 
 impl Pipeline {
@@ -77,26 +207,85 @@ impl Pipeline {
         self.passes.push(pass);
     }
 
-    pub fn build(&self) -> Exec<'_> {
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    // Templates are registered once under their own source as the name, so
+    // an identical template used on many elements is compiled exactly once.
+    fn build_environment(&self) -> Result<Environment<'_>, Error> {
+        let mut environment = Environment::new();
+        filters::register(&mut environment);
+        for pass in &self.passes {
+            for selector in &pass.selectors {
+                for action in &selector.actions {
+                    if let Action::SetInnerContent { template } = action {
+                        if environment.get_template(template).is_err() {
+                            environment
+                                .add_template(template, template)
+                                .map_err(|e| Error::template(&selector.selector, template, e))?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(environment)
+    }
+
+    fn build_with_environment<'h>(&'h self, environment: Rc<Environment<'h>>) -> Exec<'h> {
+        // Each pass's rewriter forwards its output into the previously-built
+        // one, so the pass built last ends up outermost and sees the raw
+        // input first. Build in reverse so the *first*-added pass (the first
+        // `add_pass` call) is that outermost rewriter, making passes run in
+        // the order they were added.
         let mut rewriter = None;
         let mut root_state = None;
-        for pass in &self.passes {
-            let (prev, state) = pass.build(rewriter);
+        for pass in self.passes.iter().rev() {
+            let (prev, state) = pass.build(rewriter, environment.clone());
             rewriter = Some(prev);
-            if root_state.is_none() {
-                root_state = Some(state);
-            }
+            root_state = Some(state);
         }
         Exec {
             rewriter: rewriter.unwrap(),
             root_state: root_state.unwrap(),
+            output_mode: self.output_mode,
+            environment,
         }
     }
+
+    pub fn build(&self) -> Result<Exec<'_>, Error> {
+        let environment = Rc::new(self.build_environment()?);
+        Ok(self.build_with_environment(environment))
+    }
+
+    /// Runs the pipeline independently over each input, producing one output
+    /// per document — the natural shape for crawling a set of similar pages
+    /// and feeding NDJSON records to something like `jq` downstream.
+    ///
+    /// The MiniJinja environment (and its compiled templates) is built once
+    /// and shared across the whole batch; the rewriter chain and `PassState`
+    /// are rebuilt for every document, so filter/extract state never leaks
+    /// from one document into the next.
+    pub fn exec_many<I>(&self, inputs: I) -> Result<Vec<String>, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let environment = Rc::new(self.build_environment()?);
+        inputs
+            .into_iter()
+            .map(|input| {
+                let mut exec = self.build_with_environment(environment.clone());
+                exec.exec(input.as_ref())
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PassState {
     output_enabled: Vec<bool>,
+    extract_stack: Vec<ExtractFrame>,
 }
 
 impl Pass {
@@ -113,6 +302,11 @@ impl Pass {
         self.on(selector, |pass| pass.filter());
     }
 
+    pub fn sanitize(&mut self, config: SanitizeConfig) {
+        let config = Rc::new(config);
+        self.on("*", move |sel| sel.sanitize(config));
+    }
+
     pub fn default_output(&self) -> bool {
         for selector in &self.selectors {
             for action in &selector.actions {
@@ -127,20 +321,23 @@ impl Pass {
     pub fn build<'h>(
         &'h self,
         next: Option<HtmlRewriter<'h, PipelineSink<'h>>>,
+        environment: Rc<Environment<'h>>,
     ) -> (HtmlRewriter<'_, PipelineSink<'_>>, Rc<RefCell<PassState>>) {
         let mut settings = Settings::default();
         let state = Rc::new(RefCell::new(PassState {
             output_enabled: vec![self.default_output()],
+            extract_stack: Vec::new(),
         }));
         for selector in &self.selectors {
-            let state = state.clone();
+            let state_for_element = state.clone();
+            let environment = environment.clone();
             settings
                 .element_content_handlers
                 .push(element!(selector.selector, move |el| {
-                    let state = state.clone();
+                    let state = state_for_element.clone();
                     let selector = selector.clone();
                     for action in &selector.actions {
-                        action.enter(el, &mut state.borrow_mut());
+                        action.enter(el, &mut state.borrow_mut(), &environment, &selector.selector)?;
                     }
                     el.on_after_end_tag(move |tag| {
                         for action in &selector.actions {
@@ -150,6 +347,19 @@ impl Pass {
                     })?;
                     Ok(())
                 }));
+            if selector.wants_text() {
+                let state_for_text = state.clone();
+                settings
+                    .element_content_handlers
+                    .push(text!(selector.selector, move |chunk| {
+                        for frame in state_for_text.borrow_mut().extract_stack.iter_mut() {
+                            if frame.fields.iter().any(|(_, field)| field.wants_text()) {
+                                frame.text_buf.push_str(chunk.as_str());
+                            }
+                        }
+                        Ok(())
+                    }));
+            }
         }
         (
             HtmlRewriter::new(
@@ -165,7 +375,13 @@ impl Pass {
 }
 
 impl Action {
-    pub fn enter(&self, el: &mut Element, state: &mut PassState) {
+    pub fn enter(
+        &self,
+        el: &mut Element,
+        state: &mut PassState,
+        environment: &Environment,
+        selector_name: &str,
+    ) -> Result<(), Error> {
         match self {
             Action::Filter => {
                 state.output_enabled.push(true);
@@ -179,24 +395,81 @@ impl Action {
                 let rv = regex.replace_all(&val, replacement.as_str());
                 el.set_attribute(attr, &rv).unwrap();
             }
-            Action::SetInnerContent { template } => el.set_inner_content(
-                &ExecState::with(|state| {
-                    let attributes = el
-                        .attributes()
-                        .iter()
-                        .map(|x| (x.name(), x.value()))
-                        .collect::<BTreeMap<_, _>>();
+            Action::SetInnerContent { template } => {
+                let attributes = el
+                    .attributes()
+                    .iter()
+                    .map(|x| (x.name(), x.value()))
+                    .collect::<BTreeMap<_, _>>();
+                let rendered = ExecState::with(|state| {
                     state.render_template(
-                        &template,
+                        environment,
+                        template,
                         context! {
                             tag => el.tag_name(),
                             attributes,
                         },
                     )
-                }),
-                ContentType::Html,
-            ),
+                })
+                .map_err(|e| Error::template(selector_name, template, e))?;
+                el.set_inner_content(&rendered, ContentType::Html);
+            }
+            Action::Extract { fields } => {
+                let mut record = Map::new();
+                for (key, field) in fields {
+                    if let Field::Attr(attr) = field {
+                        let val = el.get_attribute(attr).unwrap_or_default();
+                        record.insert(key.clone(), Value::String(val));
+                    }
+                }
+                state.extract_stack.push(ExtractFrame {
+                    fields: fields.clone(),
+                    record,
+                    text_buf: String::new(),
+                });
+            }
+            Action::Sanitize { config } => {
+                let tag = el.tag_name();
+                if config.blacklisted_elements.contains(&tag) {
+                    el.remove();
+                    return Ok(());
+                }
+                if !config.allowed_elements.contains(&tag) {
+                    el.remove_and_keep_content();
+                    return Ok(());
+                }
+
+                let allowed_attrs = config.allowed_attributes.get(&tag);
+                let names: Vec<String> = el.attributes().iter().map(|a| a.name()).collect();
+                for name in &names {
+                    if !allowed_attrs.is_some_and(|set| set.contains(name)) {
+                        el.remove_attribute(name);
+                        continue;
+                    }
+                    if name == "srcset" {
+                        if let Some(value) = el.get_attribute(name) {
+                            if !config.srcset_allowed(&value) {
+                                el.remove_attribute(name);
+                            }
+                        }
+                    } else if matches!(name.as_str(), "href" | "src") {
+                        if let Some(value) = el.get_attribute(name) {
+                            if !config.scheme_allowed(&value) {
+                                el.remove_attribute(name);
+                            }
+                        }
+                    }
+                }
+
+                if config.defang_images && matches!(tag.as_str(), "img" | "source") {
+                    if let Some(src) = el.get_attribute("src") {
+                        el.remove_attribute("src");
+                        let _ = el.set_attribute("data-src", &src);
+                    }
+                }
+            }
         }
+        Ok(())
     }
 
     pub fn leave(&self, tag: &str, state: &mut PassState) {
@@ -206,6 +479,18 @@ impl Action {
             }
             Action::RewriteAttribute { .. } => {}
             Action::SetInnerContent { .. } => {}
+            Action::Sanitize { .. } => {}
+            Action::Extract { .. } => {
+                if let Some(frame) = state.extract_stack.pop() {
+                    let mut record = frame.record;
+                    for (key, field) in &frame.fields {
+                        if field.wants_text() {
+                            record.insert(key.clone(), Value::String(frame.text_buf.clone()));
+                        }
+                    }
+                    ExecState::with(|state| state.push_record(Value::Object(record)));
+                }
+            }
         }
     }
 }
@@ -228,4 +513,161 @@ impl Selector {
     pub fn filter(&mut self) {
         Box::new(self.actions.push(Action::Filter));
     }
+
+    pub fn extract(&mut self, fields: Vec<(&str, Field)>) {
+        self.actions.push(Action::Extract {
+            fields: fields.into_iter().map(|(k, f)| (k.into(), f)).collect(),
+        });
+    }
+
+    pub fn sanitize(&mut self, config: Rc<SanitizeConfig>) {
+        self.actions.push(Action::Sanitize { config });
+    }
+
+    fn wants_text(&self) -> bool {
+        self.actions.iter().any(|action| match action {
+            Action::Extract { fields } => fields.iter().any(|(_, field)| field.wants_text()),
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SanitizeConfig {
+        let mut config = SanitizeConfig::new();
+        config.allow_scheme("http");
+        config.allow_scheme("https");
+        config
+    }
+
+    #[test]
+    fn scheme_allowed_accepts_allowlisted_and_relative() {
+        let config = config();
+        assert!(config.scheme_allowed("https://example.com"));
+        assert!(config.scheme_allowed("/relative/path"));
+        assert!(config.scheme_allowed("path/without/scheme"));
+    }
+
+    #[test]
+    fn scheme_allowed_rejects_non_allowlisted_scheme() {
+        let config = config();
+        assert!(!config.scheme_allowed("javascript:alert(1)"));
+        assert!(!config.scheme_allowed("data:text/html,<script>alert(1)</script>"));
+    }
+
+    #[test]
+    fn scheme_allowed_rejects_obfuscated_scheme() {
+        let config = config();
+        assert!(!config.scheme_allowed("\tjavascript:alert(1)"));
+        assert!(!config.scheme_allowed(" javascript:alert(1)"));
+        assert!(!config.scheme_allowed("java\nscript:alert(1)"));
+    }
+
+    #[test]
+    fn srcset_allowed_checks_every_candidate() {
+        let config = config();
+        assert!(config.srcset_allowed("a.png 1x, b.png 2x"));
+        assert!(!config.srcset_allowed("a.png 1x, javascript:alert(1) 2x"));
+    }
+
+    #[test]
+    fn nested_extract_frames_accumulate_independently() {
+        let mut pipeline = Pipeline::new();
+        pipeline.set_output_mode(OutputMode::Json);
+        pipeline.add_pass(|pass| {
+            pass.on(".item", |sel| sel.extract(vec![("title", Field::Text)]));
+        });
+
+        let html = r#"<div class="item">outer<div class="item">inner</div>tail</div>"#;
+        let outputs = pipeline.exec_many([html]).unwrap();
+        let records: Value = serde_json::from_str(&outputs[0]).unwrap();
+
+        assert_eq!(
+            records,
+            serde_json::json!([
+                {"title": "inner"},
+                {"title": "outerinnertail"},
+            ])
+        );
+    }
+
+    #[test]
+    fn set_inner_content_renders_template_with_tag_and_attributes() {
+        let mut pipeline = Pipeline::new();
+        pipeline.add_pass(|pass| {
+            pass.on("a", |sel| sel.set_inner_content("{{ tag }}:{{ attributes.href }}"));
+        });
+
+        let outputs = pipeline
+            .exec_many([r#"<a href="/x">old</a>"#])
+            .unwrap();
+        assert_eq!(outputs[0], r#"<a href="/x">a:/x</a>"#);
+    }
+
+    #[test]
+    fn identical_set_inner_content_templates_render_for_every_selector() {
+        // Two selectors reusing the exact same template source go through the
+        // `build_environment` dedup (it only calls `add_template` the first
+        // time a given template string is seen); this checks that both
+        // selectors still render from the one shared registration.
+        let mut pipeline = Pipeline::new();
+        pipeline.add_pass(|pass| {
+            pass.on("a", |sel| sel.set_inner_content("{{ tag }}"));
+            pass.on("b", |sel| sel.set_inner_content("{{ tag }}"));
+        });
+
+        let outputs = pipeline
+            .exec_many([r#"<a href="/x">x</a><b>y</b>"#])
+            .unwrap();
+        assert_eq!(outputs[0], "<a href=\"/x\">a</a><b>b</b>");
+    }
+
+    #[test]
+    fn invalid_set_inner_content_template_is_a_reported_error_not_a_panic() {
+        let mut pipeline = Pipeline::new();
+        pipeline.add_pass(|pass| {
+            pass.on("a", |sel| sel.set_inner_content("{{ unclosed"));
+        });
+
+        let err = pipeline.build().unwrap_err();
+        assert!(err.to_string().contains("template error for selector `a`"));
+    }
+
+    #[test]
+    fn exec_many_does_not_leak_state_across_documents() {
+        let mut pipeline = Pipeline::new();
+        pipeline.set_output_mode(OutputMode::Json);
+        pipeline.add_pass(|pass| {
+            pass.on(".item", |sel| sel.extract(vec![("title", Field::Text)]));
+        });
+
+        let outputs = pipeline
+            .exec_many([
+                r#"<div class="item">first</div>"#,
+                r#"<div class="item">second</div>"#,
+            ])
+            .unwrap();
+
+        let first: Value = serde_json::from_str(&outputs[0]).unwrap();
+        let second: Value = serde_json::from_str(&outputs[1]).unwrap();
+        assert_eq!(first, serde_json::json!([{"title": "first"}]));
+        assert_eq!(second, serde_json::json!([{"title": "second"}]));
+    }
+
+    #[test]
+    fn exec_many_produces_one_output_per_input_in_order() {
+        let mut pipeline = Pipeline::new();
+        pipeline.add_pass(|pass| {
+            pass.on("p", |sel| sel.set_inner_content("{{ tag }}"));
+        });
+
+        let outputs = pipeline
+            .exec_many(["<p>one</p>", "<p>two</p>", "<p>three</p>"])
+            .unwrap();
+
+        assert_eq!(outputs, vec!["<p>p</p>", "<p>p</p>", "<p>p</p>"]);
+    }
 }
@@ -0,0 +1,428 @@
+//! The compact expression language behind `-a` and `-s`.
+//!
+//! `-a` takes a CSS selector followed by pipe-separated action clauses, e.g.
+//!
+//! ```text
+//! a[href] | rewrite-attr href /^http:/https:/ | set-inner "{{ attributes|tojson }}"
+//! div.item | extract{title: text, url: attr:href}
+//! ```
+//!
+//! `-s` takes a comma-separated list of sanitizer directives instead, e.g.
+//!
+//! ```text
+//! element:p,element:a,attribute:a.href,scheme:http,scheme:https,defang-images
+//! ```
+//!
+//! This module is a hand-written tokenizer-free recursive-descent parser: it
+//! walks the expression a character at a time rather than pre-tokenizing,
+//! since the grammar of an action's arguments (e.g. the `/regex/replacement/`
+//! form) depends on which action it is.
+
+use std::fmt;
+
+use regex::Regex;
+
+use crate::error::Error;
+use crate::pipeline::{Field, SanitizeConfig};
+
+/// A fully parsed `-a` expression.
+#[derive(Debug)]
+pub struct Action {
+    pub selector: String,
+    pub clauses: Vec<Clause>,
+}
+
+#[derive(Debug)]
+pub enum Clause {
+    Filter,
+    RewriteAttr {
+        attr: String,
+        regex: String,
+        replacement: String,
+    },
+    SetInner {
+        template: String,
+    },
+    Extract {
+        fields: Vec<(String, Field)>,
+    },
+}
+
+/// A parse failure, with a 1-based column so the user can find the offending
+/// character in their `-a` expression.
+#[derive(Debug)]
+pub struct ParseError {
+    message: String,
+    column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::new(e.to_string())
+    }
+}
+
+pub fn parse_action(expr: &str) -> Result<Action, ParseError> {
+    let mut parser = Parser::new(expr);
+    let action = parser.parse_action()?;
+    parser.expect_end()?;
+    Ok(action)
+}
+
+/// Parses a `-s` expression: a comma-separated list of sanitizer directives,
+/// e.g. `element:p,element:a,attribute:a.href,scheme:http,defang-images`.
+pub fn parse_sanitize(expr: &str) -> Result<SanitizeConfig, ParseError> {
+    let mut parser = Parser::new(expr);
+    let config = parser.parse_sanitize_directives()?;
+    parser.expect_end()?;
+    Ok(config)
+}
+
+struct Parser<'s> {
+    input: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn new(input: &'s str) -> Self {
+        Parser { input, pos: 0 }
+    }
+
+    fn column(&self) -> usize {
+        self.input[..self.pos].chars().count() + 1
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            column: self.column(),
+        }
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> Result<(), ParseError> {
+        if self.peek() == Some(expected) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{expected}`")))
+        }
+    }
+
+    /// Everything up to (not including) the first occurrence of `delim`, or
+    /// the end of input.
+    fn take_until(&mut self, delim: char) -> &'s str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == delim {
+                break;
+            }
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn parse_ident(&mut self) -> Result<&'s str, ParseError> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '-' || c == '_') {
+            self.bump();
+        }
+        if self.pos == start {
+            return Err(self.error("expected an identifier"));
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn parse_quoted_string(&mut self) -> Result<String, ParseError> {
+        self.eat('"')?;
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string literal")),
+                Some('"') => break,
+                Some('\\') => match self.bump() {
+                    Some(c) => out.push(c),
+                    None => return Err(self.error("unterminated string literal")),
+                },
+                Some(c) => out.push(c),
+            }
+        }
+        Ok(out)
+    }
+
+    /// `/regex/replacement/`
+    fn parse_slash_delimited(&mut self) -> Result<(String, String), ParseError> {
+        self.eat('/')?;
+        let regex = self.take_until('/').to_string();
+        self.eat('/')?;
+        let replacement = self.take_until('/').to_string();
+        self.eat('/')?;
+        Ok((regex, replacement))
+    }
+
+    fn parse_field_spec(&mut self) -> Result<Field, ParseError> {
+        let head = self.parse_ident()?;
+        match head {
+            "text" => Ok(Field::Text),
+            "attr" => {
+                self.eat(':')?;
+                let attr = self.parse_ident()?.to_string();
+                Ok(Field::Attr(attr))
+            }
+            other => Err(self.error(format!("unknown field spec `{other}`"))),
+        }
+    }
+
+    fn parse_fields(&mut self) -> Result<Vec<(String, Field)>, ParseError> {
+        let mut fields = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some('}') {
+            return Ok(fields);
+        }
+        loop {
+            self.skip_ws();
+            let key = self.parse_ident()?.to_string();
+            self.skip_ws();
+            self.eat(':')?;
+            self.skip_ws();
+            let field = self.parse_field_spec()?;
+            fields.push((key, field));
+            self.skip_ws();
+            if self.peek() == Some(',') {
+                self.bump();
+                continue;
+            }
+            break;
+        }
+        Ok(fields)
+    }
+
+    fn parse_clause(&mut self) -> Result<Clause, ParseError> {
+        let name = self.parse_ident()?.to_string();
+        self.skip_ws();
+        match name.as_str() {
+            "filter" => Ok(Clause::Filter),
+            "rewrite-attr" => {
+                let attr = self.parse_ident()?.to_string();
+                self.skip_ws();
+                let (regex, replacement) = self.parse_slash_delimited()?;
+                if let Err(e) = Regex::new(&regex) {
+                    return Err(self.error(format!("invalid regex `{regex}`: {e}")));
+                }
+                Ok(Clause::RewriteAttr {
+                    attr,
+                    regex,
+                    replacement,
+                })
+            }
+            "set-inner" => Ok(Clause::SetInner {
+                template: self.parse_quoted_string()?,
+            }),
+            "extract" => {
+                self.eat('{')?;
+                let fields = self.parse_fields()?;
+                self.eat('}')?;
+                Ok(Clause::Extract { fields })
+            }
+            other => Err(self.error(format!("unknown action `{other}`"))),
+        }
+    }
+
+    fn parse_action(&mut self) -> Result<Action, ParseError> {
+        let selector = self.take_until('|').trim().to_string();
+        if selector.is_empty() {
+            return Err(self.error("expected a CSS selector"));
+        }
+        let mut clauses = Vec::new();
+        while self.peek() == Some('|') {
+            self.bump();
+            self.skip_ws();
+            clauses.push(self.parse_clause()?);
+            self.skip_ws();
+        }
+        if clauses.is_empty() {
+            return Err(self.error("expected at least one `| action` clause"));
+        }
+        Ok(Action { selector, clauses })
+    }
+
+    fn parse_sanitize_directives(&mut self) -> Result<SanitizeConfig, ParseError> {
+        let mut config = SanitizeConfig::new();
+        self.skip_ws();
+        if self.peek().is_none() {
+            return Ok(config);
+        }
+        loop {
+            self.skip_ws();
+            let name = self.parse_ident()?.to_string();
+            match name.as_str() {
+                "defang-images" => {
+                    config.defang_images(true);
+                }
+                "element" => {
+                    self.eat(':')?;
+                    config.allow_element(self.parse_ident()?);
+                }
+                "blacklist" => {
+                    self.eat(':')?;
+                    config.blacklist_element(self.parse_ident()?);
+                }
+                "scheme" => {
+                    self.eat(':')?;
+                    config.allow_scheme(self.parse_ident()?);
+                }
+                "attribute" => {
+                    self.eat(':')?;
+                    let element = self.parse_ident()?.to_string();
+                    self.eat('.')?;
+                    config.allow_attribute(&element, self.parse_ident()?);
+                }
+                other => return Err(self.error(format!("unknown sanitize directive `{other}`"))),
+            }
+            self.skip_ws();
+            if self.peek() == Some(',') {
+                self.bump();
+                continue;
+            }
+            break;
+        }
+        Ok(config)
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        self.skip_ws();
+        if self.peek().is_some() {
+            return Err(self.error("unexpected trailing input"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_filter_clause() {
+        let action = parse_action(".ad | filter").unwrap();
+        assert_eq!(action.selector, ".ad");
+        assert!(matches!(action.clauses.as_slice(), [Clause::Filter]));
+    }
+
+    #[test]
+    fn parses_rewrite_attr_clause() {
+        let action = parse_action("a[href] | rewrite-attr href /^http:/https:/").unwrap();
+        match &action.clauses[..] {
+            [Clause::RewriteAttr {
+                attr,
+                regex,
+                replacement,
+            }] => {
+                assert_eq!(attr, "href");
+                assert_eq!(regex, "^http:");
+                assert_eq!(replacement, "https:");
+            }
+            other => panic!("unexpected clauses: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_set_inner_clause() {
+        let action = parse_action(r#"div | set-inner "hello""#).unwrap();
+        assert!(matches!(
+            &action.clauses[..],
+            [Clause::SetInner { template }] if template == "hello"
+        ));
+    }
+
+    #[test]
+    fn parses_extract_clause_with_multiple_fields() {
+        let action =
+            parse_action("div.item | extract{title: text, url: attr:href}").unwrap();
+        match &action.clauses[..] {
+            [Clause::Extract { fields }] => {
+                assert_eq!(fields.len(), 2);
+                assert_eq!(fields[0].0, "title");
+                assert!(matches!(fields[0].1, Field::Text));
+                assert_eq!(fields[1].0, "url");
+                assert!(matches!(&fields[1].1, Field::Attr(a) if a == "href"));
+            }
+            other => panic!("unexpected clauses: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_regex_in_rewrite_attr() {
+        let err = parse_action("a | rewrite-attr href /[/x/").unwrap_err();
+        assert!(err.to_string().contains("invalid regex"));
+    }
+
+    #[test]
+    fn rejects_unknown_field_spec() {
+        let err = parse_action("div | extract{body: html}").unwrap_err();
+        assert!(err.to_string().contains("unknown field spec"));
+    }
+
+    #[test]
+    fn rejects_missing_selector() {
+        let err = parse_action("| filter").unwrap_err();
+        assert!(err.to_string().contains("expected a CSS selector"));
+    }
+
+    #[test]
+    fn rejects_trailing_input() {
+        let err = parse_action("div | filter extra").unwrap_err();
+        assert!(err.to_string().contains("unexpected trailing input"));
+    }
+
+    #[test]
+    fn parses_sanitize_directives() {
+        let config = parse_sanitize(
+            "element:p,element:a,blacklist:script,attribute:a.href,scheme:http,scheme:https,defang-images",
+        )
+        .unwrap();
+        let debug = format!("{config:?}");
+        assert!(debug.contains(r#"allowed_elements: {"a", "p"}"#));
+        assert!(debug.contains(r#"blacklisted_elements: {"script"}"#));
+        assert!(debug.contains(r#"allowed_attributes: {"a": {"href"}}"#));
+        assert!(debug.contains(r#"allowed_schemes: {"http", "https"}"#));
+        assert!(debug.contains("defang_images: true"));
+    }
+
+    #[test]
+    fn empty_sanitize_expr_is_a_no_op_config() {
+        let config = parse_sanitize("").unwrap();
+        assert!(format!("{config:?}").contains("defang_images: false"));
+    }
+
+    #[test]
+    fn rejects_unknown_sanitize_directive() {
+        let err = parse_sanitize("bogus:foo").unwrap_err();
+        assert!(err.to_string().contains("unknown sanitize directive"));
+    }
+}